@@ -36,6 +36,7 @@ mod error;
 pub use error::*;
 mod ordqueue;
 use ordqueue::*;
+mod scratch;
 pub mod progress;
 use progress::*;
 pub mod c_api;
@@ -46,7 +47,50 @@ use std::sync::Arc;
 use std::borrow::Cow;
 use std::thread;
 
-type DecodedImage = CatResult<(ImgVec<RGBA8>, u16)>;
+type DecodedImage = CatResult<(ImgVec<RGBA8>, FrameTiming)>;
+
+/// How a caller specified the timing of a frame.
+#[derive(Copy, Clone)]
+enum FrameTiming {
+    /// Explicit GIF delay in centiseconds (1/100s), as supplied by the caller.
+    Delay(u16),
+    /// Presentation timestamp in seconds. The actual delay is derived later
+    /// from the gap to the next frame, tracking cumulative playback time so
+    /// that per-frame rounding error doesn't accumulate over long animations.
+    Timestamp(f64),
+}
+
+/// Running state used to turn presentation timestamps into GIF delays.
+struct TimingState {
+    /// Centiseconds emitted so far. Carries rounding/clamping debt forward, and
+    /// also advances for explicit `Delay` frames so mixing the two timing modes
+    /// keeps the cumulative baseline correct.
+    emitted_cs: i64,
+    /// Duration of the previous frame, reused for the final frame (which has
+    /// no successor to measure against). Seeded to 0.1s so a lone frame isn't
+    /// shown for zero time.
+    last_interval: f64,
+    /// First timestamp seen, less the playback time already emitted before it.
+    /// Subtracted from presentation times so a nonzero start offset (as real
+    /// muxers emit) isn't charged to frame 0, nor earlier `Delay` frames'
+    /// time to the first timestamped frame.
+    anchor: Option<f64>,
+}
+
+impl Default for TimingState {
+    fn default() -> Self {
+        TimingState { emitted_cs: 0, last_interval: 0.1, anchor: None }
+    }
+}
+
+/// How many times the encoded animation should play before stopping.
+#[derive(Copy, Clone)]
+pub enum Repeat {
+    /// Loop forever.
+    Infinite,
+    /// Play exactly this many times, then stop on the last frame.
+    Finite(u16),
+}
 
 #[derive(Copy, Clone)]
 pub struct Settings {
@@ -56,10 +100,30 @@ pub struct Settings {
     pub height: Option<u32>,
     /// 1-100
     pub quality: u8,
-    /// If true, looping is disabled
-    pub once: bool,
-    /// Lower quality, but faster encode
+    /// How many times the animation should play
+    pub repeat: Repeat,
+    /// Lower quality, but faster encode. Forces the fastest quantization speed.
     pub fast: bool,
+    /// imagequant speed/effort, 1 (slowest, best) to 10 (fastest). Ignored when `fast` is set.
+    pub speed: u8,
+    /// Error-diffusion dithering amount, 0.0 (off — best for flat UI/pixel-art) to 1.0 (max).
+    pub dithering: f32,
+    /// Quantize all frames against a single shared palette instead of a
+    /// per-frame one. Smaller files and no inter-frame palette shimmer, but
+    /// lower quality when colors vary strongly between frames.
+    pub global_palette: bool,
+    /// Spill quantized frames to a temporary scratch file and stream them back
+    /// in order, capping peak memory to a few frames regardless of frame count.
+    /// Trades disk space for RAM; ignored in `global_palette` mode.
+    pub scratch_file: bool,
+}
+
+impl Settings {
+    /// imagequant speed to use: the fastest when `fast` is set, otherwise the
+    /// explicit `speed` clamped to imagequant's valid 1..=10 range.
+    fn liq_speed(&self) -> i32 {
+        if self.fast { 10 } else { i32::from(self.speed.max(1).min(10)) }
+    }
 }
 
 /// Collect frames that will be encoded
@@ -79,8 +143,15 @@ pub struct Writer {
 }
 
 struct GIFFrame {
+    /// Full-canvas quantized image. Only the `left`/`top`/`width`/`height`
+    /// sub-rectangle is actually emitted; the rest is left to the previous frame.
     image: ImgVec<u8>,
     pal: Vec<RGBA8>,
+    left: u16,
+    top: u16,
+    width: u16,
+    height: u16,
+    dispose: DisposalMethod,
     delay: u16,
 }
 
@@ -112,17 +183,35 @@ impl Collector {
     /// Frame index starts at 0. Set each frame only once, but you can set them in any order.
     /// Frame delay is in GIF units (1/100s).
     pub fn add_frame_rgba(&mut self, frame_index: usize, image: ImgVec<RGBA8>, delay: u16) -> CatResult<()> {
-        self.queue.push(frame_index, Ok((Self::resized(image, self.width, self.height), delay)))
+        self.queue.push(frame_index, Ok((Self::resized(image, self.width, self.height), FrameTiming::Delay(delay))))
+    }
+
+    /// Like `add_frame_rgba`, but timing is given as a presentation timestamp in seconds
+    /// (the moment the frame should appear). GIF can only express 1/100s steps, so delays
+    /// are derived from the gaps between timestamps while tracking cumulative playback time,
+    /// keeping total duration accurate instead of letting per-frame rounding drift.
+    pub fn add_frame_rgba_at(&mut self, frame_index: usize, image: ImgVec<RGBA8>, presentation_timestamp: f64) -> CatResult<()> {
+        self.queue.push(frame_index, Ok((Self::resized(image, self.width, self.height), FrameTiming::Timestamp(presentation_timestamp))))
     }
 
     /// Read and decode a PNG file from disk. Frame index starts at 0. Frame delay is in GIF units (1/100s)
     pub fn add_frame_png_file(&mut self, frame_index: usize,  path: PathBuf, delay: u16) -> CatResult<()> {
+        self.add_frame_png_file_timed(frame_index, path, FrameTiming::Delay(delay))
+    }
+
+    /// Like `add_frame_png_file`, but timing is given as a presentation timestamp in seconds.
+    /// See `add_frame_rgba_at`.
+    pub fn add_frame_png_file_at(&mut self, frame_index: usize, path: PathBuf, presentation_timestamp: f64) -> CatResult<()> {
+        self.add_frame_png_file_timed(frame_index, path, FrameTiming::Timestamp(presentation_timestamp))
+    }
+
+    fn add_frame_png_file_timed(&mut self, frame_index: usize, path: PathBuf, timing: FrameTiming) -> CatResult<()> {
         let width = self.width;
         let height = self.height;
         let image = lodepng::decode32_file(&path)
             .chain_err(|| format!("Can't load {}", path.display()))?;
 
-        self.queue.push(frame_index, Ok((Self::resized(ImgVec::new(image.buffer, image.width, image.height), width, height), delay)))
+        self.queue.push(frame_index, Ok((Self::resized(ImgVec::new(image.buffer, image.width, image.height), width, height), timing)))
     }
 
     fn resized(mut image: ImgVec<RGBA8>, width: Option<u32>, height: Option<u32>) -> ImgVec<RGBA8> {
@@ -154,9 +243,7 @@ impl Writer {
     /// `background` is the previous frame.
     fn quantize(image: ImgRef<RGBA8>, importance_map: &[u8], background: Option<ImgRef<RGBA8>>, settings: &Settings) -> CatResult<(ImgVec<u8>, Vec<RGBA8>)> {
         let mut liq = Attributes::new();
-        if settings.fast {
-            liq.set_speed(10);
-        }
+        liq.set_speed(settings.liq_speed());
         let quality = if background.is_some() { // not first frame
             settings.quality.into()
         } else {
@@ -170,7 +257,7 @@ impl Writer {
         }
         img.add_fixed_color(RGBA8::new(0,0,0,0));
         let mut res = liq.quantize(&img)?;
-        res.set_dithering_level(0.5);
+        res.set_dithering_level(settings.dithering);
 
         let (pal, pal_img) = res.remapped(&mut img)?;
         debug_assert_eq!(img.width() * img.height(), pal_img.len());
@@ -178,11 +265,12 @@ impl Writer {
         Ok((Img::new(pal_img, img.width(), img.height()), pal))
     }
 
-    fn write_frames<W: Write + Send>(write_queue_iter: OrdQueueIter<Arc<GIFFrame>>, outfile: W, settings: &Settings, reporter: &mut ProgressReporter) -> CatResult<()> {
+    fn write_frames<W: Write + Send, I: Iterator<Item = CatResult<Arc<GIFFrame>>>>(frames: I, outfile: W, settings: &Settings, reporter: &mut ProgressReporter) -> CatResult<()> {
         let mut enc = WriteInitState::Uninit(outfile);
 
-        for f in write_queue_iter {
-            let GIFFrame {ref pal, ref image, delay} = *f;
+        for f in frames {
+            let f = f?;
+            let GIFFrame {ref pal, ref image, left, top, width, height, dispose, delay} = *f;
             reporter.increase();
 
             let mut transparent_index = None;
@@ -196,10 +284,15 @@ impl Writer {
 
             enc = match enc {
                 WriteInitState::Uninit(w) => {
-                    let mut enc = Encoder::new(w, image.width() as u16, image.height() as u16, &[])?;
-                    if !settings.once {
-                        enc.write_extension(gif::ExtensionData::Repetitions(gif::Repeat::Infinite))?;
-                    }
+                    // In global-palette mode every frame shares the same table, so
+                    // write it once as the global color table and omit it per frame.
+                    let global = if settings.global_palette { pal_rgb.clone() } else { Vec::new() };
+                    let mut enc = Encoder::new(w, image.width() as u16, image.height() as u16, &global)?;
+                    let repeat = match settings.repeat {
+                        Repeat::Infinite => gif::Repeat::Infinite,
+                        Repeat::Finite(n) => gif::Repeat::Finite(n),
+                    };
+                    enc.write_extension(gif::ExtensionData::Repetitions(repeat))?;
                     WriteInitState::Init(enc)
                 },
                 x => x,
@@ -209,18 +302,25 @@ impl Writer {
                 _ => unreachable!(),
             };
 
+            // Copy out only the changed sub-rectangle, respecting the full-canvas stride.
+            let sub = image.as_ref().sub_image(left as usize, top as usize, width as usize, height as usize);
+            let mut buffer = Vec::with_capacity(width as usize * height as usize);
+            for row in sub.rows() {
+                buffer.extend_from_slice(row);
+            }
+
             enc.write_frame(&Frame {
                 delay,
-                dispose: DisposalMethod::Keep,
+                dispose,
                 transparent: transparent_index,
                 needs_user_input: false,
-                top: 0,
-                left: 0,
-                width: image.width() as u16,
-                height: image.height() as u16,
+                top,
+                left,
+                width,
+                height,
                 interlaced: false,
-                palette: Some(pal_rgb),
-                buffer: Cow::Borrowed(&image.buf),
+                palette: if settings.global_palette { None } else { Some(pal_rgb) },
+                buffer: Cow::Owned(buffer),
             })?;
         }
         Ok(())
@@ -232,21 +332,69 @@ impl Writer {
     ///
     /// `ProgressReporter.increase()` is called each time a new frame is being written.
     pub fn write<W: Write + Send>(mut self, outfile: W, reporter: &mut ProgressReporter) -> CatResult<()> {
-        let (write_queue, write_queue_iter) = ordqueue::new(4);
         let queue_iter = self.queue_iter.take().unwrap();
+
+        // Bounded-memory mode spills quantized frames to a scratch file and streams
+        // them back in order, so only a few frames are ever resident. The global
+        // palette path already has to buffer every frame for its histogram, so
+        // spilling doesn't apply there.
+        if self.settings.scratch_file && !self.settings.global_palette {
+            let settings = self.settings.clone();
+            let mut store = scratch::FrameStore::new()?;
+            Self::make_frames(queue_iter, &mut store, &settings)?;
+            return Self::write_frames(store.drain()?, outfile, &self.settings, reporter);
+        }
+
+        let (write_queue, write_queue_iter) = ordqueue::new(4);
         let settings = self.settings.clone();
         let make_thread = thread::spawn(move || {
-            Self::make_frames(queue_iter, write_queue, &settings)
+            let mut sink = write_queue;
+            if settings.global_palette {
+                Self::make_frames_global(queue_iter, &mut sink, &settings)
+            } else {
+                Self::make_frames(queue_iter, &mut sink, &settings)
+            }
         });
-        Self::write_frames(write_queue_iter, outfile, &self.settings, reporter)?;
+        Self::write_frames(write_queue_iter.map(Ok), outfile, &self.settings, reporter)?;
         make_thread.join().unwrap()?;
         Ok(())
     }
 
-    fn make_frames(queue_iter: OrdQueueIter<DecodedImage>, mut write_queue: OrdQueue<Arc<GIFFrame>>, settings: &Settings) -> CatResult<()> {
-        let mut decode_iter = queue_iter.enumerate().map(|(i,tmp)| tmp.map(|(image, delay)|(i,image,delay)));
+    /// Convert a caller-supplied `FrameTiming` into a concrete GIF delay (centiseconds).
+    ///
+    /// Explicit delays pass through, but still advance the cumulative baseline so
+    /// they can be freely mixed with timestamps. The first timestamp seen is
+    /// anchored to the playback time emitted so far (so a nonzero start offset
+    /// isn't charged to frame 0), and timestamps are
+    /// turned into delays by tracking the ideal cumulative playback time:
+    /// `delay_n = round(end_n * 100) - already_emitted`, where `end_n` is the next
+    /// frame's timestamp. Clamps to a minimum of 2cs (renderers treat 0/1cs as
+    /// "as fast as possible") and lets the over-spend be repaid out of later
+    /// frames' budgets, so total length stays accurate.
+    fn next_delay(timing: FrameTiming, next_pts: Option<f64>, state: &mut TimingState) -> u16 {
+        match timing {
+            FrameTiming::Delay(delay) => {
+                state.emitted_cs += i64::from(delay);
+                delay
+            },
+            FrameTiming::Timestamp(pts) => {
+                let anchor = *state.anchor.get_or_insert(pts - state.emitted_cs as f64 / 100.);
+                let pts = pts - anchor;
+                let end = next_pts.map_or_else(|| pts + state.last_interval, |n| n - anchor);
+                let target = (end * 100.).round() as i64;
+                let delay = (target - state.emitted_cs).max(2);
+                state.emitted_cs += delay;
+                state.last_interval = (end - pts).max(0.);
+                delay as u16
+            },
+        }
+    }
+
+    fn make_frames(queue_iter: OrdQueueIter<DecodedImage>, sink: &mut dyn FrameSink, settings: &Settings) -> CatResult<()> {
+        let mut decode_iter = queue_iter.enumerate().map(|(i,tmp)| tmp.map(|(image, timing)|(i,image,timing)));
 
         let mut screen = None;
+        let mut timing_state = TimingState::default();
         let mut curr_frame = if let Some(a) = decode_iter.next() {
             Some(a?)
         } else {
@@ -259,7 +407,7 @@ impl Writer {
             None
         };
 
-        while let Some((i, image, delay)) = curr_frame.take() {
+        while let Some((i, image, timing)) = curr_frame.take() {
             curr_frame = next_frame.take();
             next_frame = if let Some(a) = decode_iter.next() {
                 Some(a?)
@@ -267,6 +415,17 @@ impl Writer {
                 None
             };
 
+            // The frame directly after `image` is now held in `curr_frame`; its
+            // timestamp bounds how long `image` is displayed.
+            let next_pts = match curr_frame {
+                Some((_, _, FrameTiming::Timestamp(pts))) => Some(pts),
+                _ => None,
+            };
+            let delay = Self::next_delay(timing, next_pts, &mut timing_state);
+
+            // The immediate successor's pixels, used to decide this frame's disposal.
+            let successor = curr_frame.as_ref().map(|&(_, ref img, _)| img.as_ref());
+
             if let Some((_, ref next, _)) = next_frame {
                 if next.width() != image.width() || next.height() != image.height() {
                     Err(format!("Frame {} has wrong size ({}×{}, expected {}×{})", i+1,
@@ -318,19 +477,171 @@ impl Writer {
                 Self::quantize(image.as_ref(), &importance_map, bg, settings)?
             };
 
-            let transparent_index = image8_pal.iter().position(|p| p.a == 0).map(|i| i as u8);
-            let frame = Arc::new(GIFFrame {
-                image: image8,
-                pal: image8_pal,
-                delay,
-            });
+            Self::composite_and_push(i, image8, image8_pal, delay, successor, screen, sink)?;
+        }
+
+        Ok(())
+    }
+
+    /// Encode frames using a single palette shared across the whole animation.
+    ///
+    /// Accumulates a colour histogram over every decoded frame, quantizes once to
+    /// a fixed <=256 colour table, then remaps each frame against it. This avoids
+    /// the per-frame palette "shimmer" some viewers show, at the cost of quality
+    /// for content whose colours vary strongly between frames.
+    fn make_frames_global(queue_iter: OrdQueueIter<DecodedImage>, sink: &mut dyn FrameSink, settings: &Settings) -> CatResult<()> {
+        // The histogram pass needs every frame in memory, so buffer them in order.
+        let frames: Vec<(ImgVec<RGBA8>, FrameTiming)> = queue_iter.collect::<CatResult<Vec<_>>>()?;
+        if frames.is_empty() {
+            Err("Found no usable frames to encode")?;
+        }
+
+        let mut liq = Attributes::new();
+        liq.set_speed(settings.liq_speed());
+        liq.set_quality(0, settings.quality.into());
+
+        let mut hist = Histogram::new(&liq);
+        for &(ref image, _) in &frames {
+            let mut img = liq.new_image_stride(image.buf.as_ref(), image.width(), image.height(), image.stride(), 0.)?;
+            hist.add_image(&liq, &mut img)?;
+        }
+        // Reserve a transparent palette entry, like the per-frame `quantize` does, so
+        // the shared table can express "leave previous pixel" and Background clears.
+        // The histogram API takes a gamma alongside the colour (0 = default sRGB),
+        // unlike the single-arg `Image::add_fixed_color` used in `quantize`.
+        hist.add_fixed_color(RGBA8::new(0,0,0,0), 0.);
+        let mut res = hist.quantize(&liq)?;
+        res.set_dithering_level(settings.dithering);
+
+        let mut screen = None;
+        let mut timing_state = TimingState::default();
+        for i in 0..frames.len() {
+            let (ref image, timing) = frames[i];
+            let next_pts = match frames.get(i + 1) {
+                Some(&(_, FrameTiming::Timestamp(pts))) => Some(pts),
+                _ => None,
+            };
+            let delay = Self::next_delay(timing, next_pts, &mut timing_state);
+            let successor = frames.get(i + 1).map(|&(ref img, _)| img.as_ref());
+
+            let (image8, image8_pal) = {
+                let mut img = liq.new_image_stride(image.buf.as_ref(), image.width(), image.height(), image.stride(), 0.)?;
+                let (pal, pal_img) = res.remapped(&mut img)?;
+                (Img::new(pal_img, img.width(), img.height()), pal)
+            };
 
-            write_queue.push(i, frame.clone())?;
-            screen.blit(Some(&frame.pal), gif::DisposalMethod::Keep, 0, 0, frame.image.as_ref(), transparent_index)?;
+            if screen.is_none() {
+                screen = Some(gif_dispose::Screen::new(image.width(), image.height(), RGBA8::new(0,0,0,0), None));
+            }
+            let screen = screen.as_mut().unwrap();
+            Self::composite_and_push(i, image8, image8_pal, delay, successor, screen, sink)?;
         }
 
         Ok(())
     }
+
+    /// Composite a quantized frame onto `screen`, compute the changed sub-rectangle
+    /// and disposal method, and push the resulting `GIFFrame` to `sink`.
+    fn composite_and_push(i: usize, image8: ImgVec<u8>, image8_pal: Vec<RGBA8>, delay: u16, successor: Option<ImgRef<RGBA8>>, screen: &mut gif_dispose::Screen, sink: &mut dyn FrameSink) -> CatResult<()> {
+        let transparent_index = image8_pal.iter().position(|p| p.a == 0).map(|i| i as u8);
+        let (full_width, full_height) = (image8.width(), image8.height());
+        let has_prev_frame = i > 0;
+
+        // Smallest rectangle that actually changed since the previous frame. The
+        // first frame always covers the whole canvas.
+        let bounds = if has_prev_frame {
+            changed_bounds(screen.pixels.as_ref(), image8.as_ref(), &image8_pal)
+        } else {
+            Some((0, 0, full_width, full_height))
+        };
+
+        screen.blit(Some(&image8_pal), gif::DisposalMethod::Keep, 0, 0, image8.as_ref(), transparent_index)?;
+
+        // If the next frame needs an opaque pixel to become transparent, overpainting
+        // can't express it, so restore the canvas to the background after this frame.
+        // GIF disposal 2 only restores *this frame's rectangle*, so the frame must cover
+        // the whole canvas for the clear to match the fully-reset `screen` model below.
+        let (left, top, width, height);
+        let dispose = if successor.map_or(false, |next| needs_clearing(screen.pixels.as_ref(), next)) {
+            for px in screen.pixels.buf.iter_mut() {
+                *px = RGBA8::new(0, 0, 0, 0);
+            }
+            let (w, h) = (full_width, full_height);
+            left = 0; top = 0; width = w; height = h;
+            DisposalMethod::Background
+        } else {
+            // GIF frames can't be empty; if nothing changed emit a minimal 1×1 patch.
+            let (l, t, w, h) = bounds.unwrap_or((0, 0, 1, 1));
+            left = l; top = t; width = w; height = h;
+            DisposalMethod::Keep
+        };
+
+        let frame = GIFFrame {
+            image: image8,
+            pal: image8_pal,
+            left: left as u16,
+            top: top as u16,
+            width: width as u16,
+            height: height as u16,
+            dispose,
+            delay,
+        };
+
+        sink.push(i, frame)
+    }
+}
+
+/// Destination for quantized frames produced by `make_frames`.
+trait FrameSink {
+    fn push(&mut self, index: usize, frame: GIFFrame) -> CatResult<()>;
+}
+
+/// The default in-memory sink: hand the frame straight to the write queue.
+impl FrameSink for OrdQueue<Arc<GIFFrame>> {
+    fn push(&mut self, index: usize, frame: GIFFrame) -> CatResult<()> {
+        OrdQueue::push(self, index, Arc::new(frame))
+    }
+}
+
+/// Bounded-memory sink: spill the frame to the scratch file instead of RAM.
+/// Frames are produced in index order, so the index isn't needed on disk.
+impl FrameSink for scratch::FrameStore {
+    fn push(&mut self, _index: usize, frame: GIFFrame) -> CatResult<()> {
+        scratch::FrameStore::push(self, &frame)
+    }
+}
+
+/// Smallest rectangle `(left, top, width, height)` enclosing pixels of the newly
+/// quantized `frame` that differ from the already-composited `screen`.
+/// Transparent frame pixels keep the previous content, so they never count as a
+/// change. Returns `None` when nothing changed.
+fn changed_bounds(screen: ImgRef<RGBA8>, frame: ImgRef<u8>, pal: &[RGBA8]) -> Option<(usize, usize, usize, usize)> {
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (usize::max_value(), usize::max_value(), 0usize, 0usize);
+    for (y, (frow, srow)) in frame.rows().zip(screen.rows()).enumerate() {
+        for (x, (&idx, &bg)) in frow.iter().zip(srow.iter()).enumerate() {
+            let px = pal[idx as usize];
+            if px.a != 0 && px != bg {
+                min_x = min_x.min(x);
+                min_y = min_y.min(y);
+                max_x = max_x.max(x);
+                max_y = max_y.max(y);
+            }
+        }
+    }
+    if min_x > max_x {
+        None
+    } else {
+        Some((min_x, min_y, max_x - min_x + 1, max_y - min_y + 1))
+    }
+}
+
+/// True if reaching `next` from the composited `screen` would require turning an
+/// opaque pixel transparent — something `DisposalMethod::Keep` can't express,
+/// since a transparent index leaves the previous pixel untouched.
+fn needs_clearing(screen: ImgRef<RGBA8>, next: ImgRef<RGBA8>) -> bool {
+    screen.rows().zip(next.rows()).any(|(srow, nrow)| {
+        srow.iter().zip(nrow.iter()).any(|(&s, &n)| n.a == 0 && s.a != 0)
+    })
 }
 
 #[inline]
@@ -342,3 +653,44 @@ fn colordiff(a: RGBA8, b: RGBA8) -> u32 {
     (i32::from(a.g as i16 - b.g as i16) * i32::from(a.g as i16 - b.g as i16)) as u32 * 3 +
     (i32::from(a.b as i16 - b.b as i16) * i32::from(a.b as i16 - b.b as i16)) as u32
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_settings(global_palette: bool) -> Settings {
+        Settings {
+            width: None,
+            height: None,
+            quality: 100,
+            repeat: Repeat::Infinite,
+            fast: true,
+            speed: 10,
+            dithering: 0.,
+            global_palette,
+            scratch_file: false,
+        }
+    }
+
+    #[test]
+    fn global_palette_roundtrip() {
+        let (mut collector, writer) = new(test_settings(true)).unwrap();
+        let collect = thread::spawn(move || {
+            for i in 0..3u16 {
+                let px = RGBA8::new((i as u8) * 80, 40, 200, 255);
+                collector.add_frame_rgba(i as usize, ImgVec::new(vec![px; 4], 2, 2), 10).unwrap();
+            }
+        });
+
+        let mut out = Vec::new();
+        writer.write(&mut out, &mut progress::NoProgress {}).unwrap();
+        collect.join().unwrap();
+
+        // The global-palette path ran end-to-end and produced a GIF: a GIF89a header
+        // (extensions such as the loop block require '89a'), a global colour table
+        // declared in the logical-screen descriptor, and a trailer.
+        assert!(out.starts_with(b"GIF89a"), "missing GIF89a header");
+        assert_eq!(0x80, out[10] & 0x80, "global colour table flag not set");
+        assert_eq!(0x3b, *out.last().unwrap(), "missing GIF trailer");
+    }
+}
@@ -0,0 +1,211 @@
+/*
+ gifski pngquant-based GIF encoder
+ © 2017 Kornel Lesiński
+
+ This program is free software: you can redistribute it and/or modify
+ it under the terms of the GNU Affero General Public License as
+ published by the Free Software Foundation, either version 3 of the
+ License, or (at your option) any later version.
+*/
+
+//! On-disk spill for quantized frames used by the bounded-memory encoding mode.
+//!
+//! Frames are appended to a temporary scratch file as they are produced and read
+//! back strictly in index order, so only a couple of frames are ever resident in
+//! RAM at once. This trades disk space for memory, which matters when encoding
+//! thousands of frames of a screen recording.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use gif::DisposalMethod;
+use imgref::ImgVec;
+use rgb::RGBA8;
+
+use super::error::*;
+use super::GIFFrame;
+
+/// Process-wide counter so concurrent encodes don't collide on a scratch path.
+static SCRATCH_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A scratch file that quantized frames are spilled to, then streamed back.
+pub struct FrameStore {
+    file: File,
+    path: PathBuf,
+    len: usize,
+    /// Set once `drain()` hands cleanup of the scratch file over to `Drain`.
+    drained: bool,
+}
+
+impl FrameStore {
+    /// Create a fresh scratch file in the system temp directory.
+    pub fn new() -> CatResult<Self> {
+        let n = SCRATCH_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("gifski-{}-{}.tmp", process::id(), n));
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path)
+            .chain_err(|| format!("Can't create scratch file {}", path.display()))?;
+        Ok(FrameStore { file, path, len: 0, drained: false })
+    }
+
+    /// Append one frame to the scratch file.
+    pub fn push(&mut self, frame: &GIFFrame) -> CatResult<()> {
+        write_frame(&mut self.file, frame)
+            .chain_err(|| format!("Can't write to scratch file {}", self.path.display()))?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Rewind and return an iterator that reads every frame back in order,
+    /// keeping only the frame being decoded resident. The scratch file is removed
+    /// once the iterator is dropped.
+    pub fn drain(mut self) -> CatResult<Drain> {
+        self.file.flush().chain_err(|| "Can't flush scratch file")?;
+        self.file.seek(SeekFrom::Start(0)).chain_err(|| "Can't rewind scratch file")?;
+        let file = self.file.try_clone().chain_err(|| "Can't reopen scratch file")?;
+        // Hand cleanup over to the returned `Drain` so our own `Drop` leaves the file.
+        self.drained = true;
+        Ok(Drain {
+            reader: BufReader::new(file),
+            remaining: self.len,
+            path: self.path.clone(),
+        })
+    }
+}
+
+impl Drop for FrameStore {
+    fn drop(&mut self) {
+        // `drain()` hands ownership of cleanup to `Drain`; only remove the file here
+        // when the store is dropped without draining (e.g. an error before it's consumed).
+        if !self.drained {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Streams frames back from a `FrameStore` in order, one at a time.
+pub struct Drain {
+    reader: BufReader<File>,
+    remaining: usize,
+    path: PathBuf,
+}
+
+impl Iterator for Drain {
+    type Item = CatResult<Arc<GIFFrame>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        Some(read_frame(&mut self.reader)
+            .chain_err(|| format!("Can't read back scratch file {}", self.path.display()))
+            .map(Arc::new))
+    }
+}
+
+impl Drop for Drain {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn write_frame<W: Write>(w: &mut W, frame: &GIFFrame) -> std::io::Result<()> {
+    let (width, height) = (frame.image.width(), frame.image.height());
+    write_u32(w, width as u32)?;
+    write_u32(w, height as u32)?;
+    // The quantized image is always contiguous (stride == width).
+    for row in frame.image.rows() {
+        w.write_all(row)?;
+    }
+
+    write_u16(w, frame.pal.len() as u16)?;
+    for p in &frame.pal {
+        w.write_all(&[p.r, p.g, p.b, p.a])?;
+    }
+
+    write_u16(w, frame.left)?;
+    write_u16(w, frame.top)?;
+    write_u16(w, frame.width)?;
+    write_u16(w, frame.height)?;
+    w.write_all(&[dispose_to_u8(frame.dispose)])?;
+    write_u16(w, frame.delay)?;
+    Ok(())
+}
+
+fn read_frame<R: Read>(r: &mut R) -> std::io::Result<GIFFrame> {
+    let width = read_u32(r)? as usize;
+    let height = read_u32(r)? as usize;
+    let mut buf = vec![0u8; width * height];
+    r.read_exact(&mut buf)?;
+    let image = ImgVec::new(buf, width, height);
+
+    let pal_len = read_u16(r)? as usize;
+    let mut pal = Vec::with_capacity(pal_len);
+    for _ in 0..pal_len {
+        let mut rgba = [0u8; 4];
+        r.read_exact(&mut rgba)?;
+        pal.push(RGBA8::new(rgba[0], rgba[1], rgba[2], rgba[3]));
+    }
+
+    let left = read_u16(r)?;
+    let top = read_u16(r)?;
+    let frame_width = read_u16(r)?;
+    let frame_height = read_u16(r)?;
+    let mut dispose = [0u8; 1];
+    r.read_exact(&mut dispose)?;
+    let delay = read_u16(r)?;
+
+    Ok(GIFFrame {
+        image,
+        pal,
+        left,
+        top,
+        width: frame_width,
+        height: frame_height,
+        dispose: dispose_from_u8(dispose[0]),
+        delay,
+    })
+}
+
+fn dispose_to_u8(d: DisposalMethod) -> u8 {
+    match d {
+        DisposalMethod::Any => 0,
+        DisposalMethod::Keep => 1,
+        DisposalMethod::Background => 2,
+        DisposalMethod::Previous => 3,
+    }
+}
+
+fn dispose_from_u8(n: u8) -> DisposalMethod {
+    match n {
+        2 => DisposalMethod::Background,
+        3 => DisposalMethod::Previous,
+        0 => DisposalMethod::Any,
+        _ => DisposalMethod::Keep,
+    }
+}
+
+fn write_u16<W: Write>(w: &mut W, v: u16) -> std::io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn write_u32<W: Write>(w: &mut W, v: u32) -> std::io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn read_u16<R: Read>(r: &mut R) -> std::io::Result<u16> {
+    let mut b = [0u8; 2];
+    r.read_exact(&mut b)?;
+    Ok(u16::from_le_bytes(b))
+}
+
+fn read_u32<R: Read>(r: &mut R) -> std::io::Result<u32> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}